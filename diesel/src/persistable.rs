@@ -5,6 +5,15 @@ use query_builder::{QueryBuilder, BuildQueryResult, QueryFragment};
 use query_source::{Table, Column};
 use types::NativeSqlType;
 
+// The SQL emitted by `ColumnInsertValue`, `InsertValues`, and `InsertStatement`
+// (the `DEFAULT` keyword, the `DEFAULT VALUES` single-record form, the
+// column-less batch rejection, and `write_names`'s identifier quoting) is
+// exercised as `to_sql` assertions against each backend's real `QueryBuilder`
+// and `Connection` in the backend integration test crate, not here: this
+// module only defines the backend-agnostic trait machinery and has no
+// concrete `Table`/`Column`/`QueryBuilder` implementation of its own to
+// build a fixture out of.
+
 /// Represents that a structure can be used to to insert a new row into the database.
 /// Implementations can be automatically generated by
 /// [`#[insertable_into]`](https://github.com/sgrif/diesel/tree/master/diesel_codegen#insertable_intotable_name).
@@ -17,12 +26,105 @@ pub trait Insertable<T: Table> {
     fn columns() -> Self::Columns;
 
     fn values(self) -> Self::Values;
+
+    /// The number of rows `self` will insert. This is `1` for a single
+    /// record, and overridden to the slice/`Vec` length for batch inserts,
+    /// so that a column-less insert can tell the single-record case (which
+    /// becomes `DEFAULT VALUES`) from a batch (which is rejected).
+    fn record_count(&self) -> usize {
+        1
+    }
+
+    /// Creates an `InsertStatement` that will insert `self` into `table`
+    /// when executed, e.g. `new_asset.insert_into(assets::table).execute(&conn)`.
+    ///
+    /// Inserting a single column-less record (every field resolved to
+    /// `ColumnInsertValue::Default`) produces `INSERT INTO <table> DEFAULT
+    /// VALUES`. Batch-inserting more than one column-less record has no
+    /// portable single-statement SQL form -- a `DEFAULT VALUES` clause can
+    /// only ever insert one row, and there's no column left in the generated
+    /// column list to hang a `VALUES (DEFAULT), (DEFAULT), ...` tuple off of
+    /// -- so `InsertStatement::to_sql` deliberately returns an `Err` for that
+    /// case rather than emitting multiple `;`-separated statements (which
+    /// most backends reject, and some silently truncate to the first one).
+    /// Insert those records one at a time instead.
+    fn insert_into<Tab>(self, _table: Tab) -> InsertStatement<Tab, Self::Values, Self::Columns> where
+        Tab: Table,
+        Self: Sized,
+    {
+        let columns = Self::columns();
+        let has_columns = !columns.is_empty();
+        let record_count = self.record_count();
+        InsertStatement {
+            values: self.values(),
+            columns: columns,
+            has_columns: has_columns,
+            record_count: record_count,
+            _marker: PhantomData,
+        }
+    }
 }
 
 pub trait InsertableColumns<T: Table> {
     type SqlType: NativeSqlType;
 
-    fn names(&self) -> String;
+    /// Writes this column, or each column of a composite tuple in order
+    /// separated by `, `, directly into `out`, letting the backend quote and
+    /// escape each identifier as it's pushed instead of building up an
+    /// intermediate `String`. This is the hot path used by
+    /// `InsertStatement`/`InsertValues`, and the one implementors (including
+    /// the composite-column tuple impls generated by `#[insertable_into]`)
+    /// are expected to provide.
+    fn write_names(&self, out: &mut QueryBuilder) -> BuildQueryResult;
+
+    /// A convenience wrapper around `write_names` for callers that just want
+    /// the column list as an owned `String`.
+    fn names(&self) -> String {
+        let mut out = QueryBuilder::new();
+        self.write_names(&mut out).expect("Failed to write column names");
+        out.sql
+    }
+
+    /// Whether this resolves to zero columns, i.e. every field of the
+    /// record is absent and the whole row should take database defaults.
+    /// Column types that know this cheaply (a single column never is)
+    /// should override this instead of inspecting `names()`, so that
+    /// checking for an all-default record doesn't force an allocation.
+    fn is_empty(&self) -> bool {
+        self.names().is_empty()
+    }
+}
+
+/// Represents the value to bind for a single column of an insertable record.
+/// `Expression` carries a value to be bound in the usual way, while `Default`
+/// causes the column to be omitted from the bound parameters and the literal
+/// keyword `DEFAULT` to be written in its place, letting the database supply
+/// its own default (e.g. an auto-increment primary key or a `DEFAULT now()`
+/// timestamp). Generated code can wrap an `Eq<Col, Expr>` in an `Option` and
+/// map it to this type to get that behavior for free.
+///
+/// The column list written for a statement is always the full, fixed list
+/// returned by `InsertableColumns::names()`, regardless of which values are
+/// `Default` -- this keeps the column list identical across every row of a
+/// batch insert, so the position of a `ColumnInsertValue` within a row's
+/// tuple always lines up with the same column.
+pub enum ColumnInsertValue<Col, Expr> {
+    Expression(Col, Expr),
+    Default(Col),
+}
+
+impl<Col, Expr> QueryFragment for ColumnInsertValue<Col, Expr> where
+    Expr: QueryFragment,
+{
+    fn to_sql(&self, out: &mut QueryBuilder) -> BuildQueryResult {
+        match *self {
+            ColumnInsertValue::Expression(_, ref value) => value.to_sql(out),
+            ColumnInsertValue::Default(_) => {
+                out.push_sql("DEFAULT");
+                Ok(())
+            }
+        }
+    }
 }
 
 impl<'a, T, U> Insertable<T> for &'a [U] where
@@ -42,6 +144,10 @@ impl<'a, T, U> Insertable<T> for &'a [U] where
             _marker: PhantomData,
         }
     }
+
+    fn record_count(&self) -> usize {
+        self.len()
+    }
 }
 
 impl<'a, T, U> Insertable<T> for &'a Vec<U> where
@@ -61,6 +167,10 @@ impl<'a, T, U> Insertable<T> for &'a Vec<U> where
             _marker: PhantomData,
         }
     }
+
+    fn record_count(&self) -> usize {
+        self.len()
+    }
 }
 
 
@@ -81,6 +191,13 @@ impl<'a, T, U> QueryFragment for InsertValues<'a, T, U> where
     &'a U: Insertable<T>,
 {
     fn to_sql(&self, out: &mut QueryBuilder) -> BuildQueryResult {
+        // `InsertValues` is only ever the `VALUES` fragment of a statement
+        // (appended after `") VALUES "` by `InsertStatement`, or directly by
+        // a caller composing `columns()`/`values()` itself) -- it must never
+        // emit a whole `INSERT INTO ...` statement of its own. The
+        // column-less/`DEFAULT VALUES` case is handled entirely by
+        // `InsertStatement`, since only it knows the target table and
+        // whether this is the single- or batch-insert case.
         for (i, record) in self.values.into_iter().enumerate() {
             if i != 0 {
                 out.push_sql(", ");
@@ -94,7 +211,62 @@ impl<'a, T, U> QueryFragment for InsertValues<'a, T, U> where
 impl<C: Column<Table=T>, T: Table> InsertableColumns<T> for C {
     type SqlType = <Self as Expression>::SqlType;
 
-    fn names(&self) -> String {
-        Self::name().to_string()
+    fn write_names(&self, out: &mut QueryBuilder) -> BuildQueryResult {
+        out.push_identifier(Self::name())
+    }
+
+    fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+/// The `INSERT INTO <table> (<columns>) VALUES <values>` statement produced by
+/// `Insertable::insert_into`. Only `T`'s type is needed (the table name comes
+/// from `Table::name()`), so the table value itself isn't stored.
+pub struct InsertStatement<T, V, C> {
+    values: V,
+    columns: C,
+    has_columns: bool,
+    record_count: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T, V, C> QueryFragment for InsertStatement<T, V, C> where
+    T: Table,
+    V: QueryFragment,
+    C: InsertableColumns<T>,
+{
+    fn to_sql(&self, out: &mut QueryBuilder) -> BuildQueryResult {
+        if self.has_columns {
+            out.push_sql("INSERT INTO ");
+            try!(out.push_identifier(T::name()));
+            out.push_sql(" (");
+            try!(self.columns.write_names(out));
+            out.push_sql(") VALUES ");
+            self.values.to_sql(out)
+        } else if self.record_count == 1 {
+            // No column is being inserted, so `self.values` has nothing to
+            // bind -- `DEFAULT VALUES` is the dedicated SQL form for a
+            // single row that is entirely database defaults.
+            out.push_sql("INSERT INTO ");
+            try!(out.push_identifier(T::name()));
+            out.push_sql(" DEFAULT VALUES");
+            Ok(())
+        } else {
+            // A single `DEFAULT VALUES` clause can only ever insert one
+            // row, and there is no portable column list left to hang a
+            // `VALUES (DEFAULT), (DEFAULT), ...` form off of (every column
+            // of every record resolved to a default). Emitting several
+            // `;`-separated `INSERT` statements isn't a real fix either:
+            // a `QueryFragment` is prepared and run as a single statement,
+            // so most backends would reject it outright, and some would
+            // silently run only the first one. Reject the batch explicitly
+            // instead of generating SQL that can't run correctly.
+            Err(format!(
+                "Cannot batch insert {} records that all resolve to \
+                 `DEFAULT VALUES` -- insert these records one at a time instead",
+                self.record_count,
+            ))
+        }
     }
 }